@@ -7,18 +7,123 @@ pub struct JxlInfo {
     pub width: u32,
     pub height: u32,
     pub num_frames: usize,
+    /// `false` when `num_frames` is an estimate rather than a counted total.
+    pub frame_count_is_exact: bool,
     pub has_alpha: bool,
+    /// Animation loop count (0 = infinite); 0 for still images.
+    pub num_loops: u32,
+    /// Ticks-per-second numerator/denominator for animation timing.
+    pub tps_numerator: u32,
+    pub tps_denominator: u32,
+}
+
+/// Decoding options supplied by the JS caller.
+///
+/// Controls the output bit depth (8 or 16); the color type itself is derived
+/// from the stream's `num_color_channels` and alpha presence so grayscale and
+/// RGB images are not needlessly inflated to RGBA.
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct DecodeConfig {
+    /// Output bit depth per sample: 8 or 16. Any other value falls back to 8.
+    pub bit_depth: u8,
+    /// Maximum total pixels (width * height) allowed before decoding, to guard
+    /// against decompression bombs from untrusted uploads.
+    pub max_pixels: u32,
+    /// Maximum cumulative byte budget for all animation frame buffers.
+    pub max_animation_bytes: u32,
+}
+
+#[wasm_bindgen]
+impl DecodeConfig {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> DecodeConfig {
+        DecodeConfig {
+            bit_depth: 8,
+            max_pixels: 64 * 1024 * 1024,         // 64M pixels
+            max_animation_bytes: 512 * 1024 * 1024, // 512MB
+        }
+    }
+}
+
+impl Default for DecodeConfig {
+    fn default() -> Self {
+        DecodeConfig::new()
+    }
+}
+
+/// Whether any extra channel is an alpha channel (rather than e.g. a spot
+/// color or depth channel).
+fn has_alpha_channel(info: &BasicInfo) -> bool {
+    info.extra_channels
+        .iter()
+        .any(|ec| ec.channel_type == ExtraChannelType::Alpha)
+}
+
+/// Reject a frame crop rectangle that falls outside the canvas. The JXL spec
+/// permits an animation frame whose position/size combination is edge-clipped
+/// against the canvas, but compositing one verbatim would index past the
+/// canvas buffer in `composite_frame`.
+fn validate_frame_rect(
+    x0: usize,
+    y0: usize,
+    frame_width: usize,
+    frame_height: usize,
+    canvas_width: usize,
+    canvas_height: usize,
+) -> Result<(), JsValue> {
+    let fits = x0.checked_add(frame_width).is_some_and(|r| r <= canvas_width)
+        && y0.checked_add(frame_height).is_some_and(|r| r <= canvas_height);
+    if !fits {
+        return Err(JsValue::from_str(&format!(
+            "Frame crop rectangle ({}x{} at {},{}) exceeds canvas bounds ({}x{})",
+            frame_width, frame_height, x0, y0, canvas_width, canvas_height
+        )));
+    }
+    Ok(())
+}
+
+/// Overflow-safe `width * height` with an explicit limit check, following the
+/// pattern libjxl uses to reject oversized images before allocation.
+fn checked_pixel_count(width: usize, height: usize, max_pixels: u64) -> Result<u64, JsValue> {
+    let num_pixels = width.wrapping_mul(height);
+    if width != 0 && num_pixels / width != height {
+        return Err(JsValue::from_str("Image dimensions overflow"));
+    }
+    let num_pixels = num_pixels as u64;
+    if num_pixels > max_pixels {
+        return Err(JsValue::from_str(&format!(
+            "Image exceeds pixel limit ({} > {} pixels)",
+            num_pixels, max_pixels
+        )));
+    }
+    Ok(num_pixels)
+}
+
+/// Pick the jxl/png color types and samples-per-pixel from the stream's
+/// color-channel count and alpha presence.
+fn select_color_type(info: &BasicInfo) -> (JxlColorType, png::ColorType, usize) {
+    let grayscale = info.num_color_channels == 1;
+    match (grayscale, has_alpha_channel(info)) {
+        (true, false) => (JxlColorType::Grayscale, png::ColorType::Grayscale, 1),
+        (true, true) => (JxlColorType::GrayscaleAlpha, png::ColorType::GrayscaleAlpha, 2),
+        (false, false) => (JxlColorType::Rgb, png::ColorType::Rgb, 3),
+        (false, true) => (JxlColorType::Rgba, png::ColorType::Rgba, 4),
+    }
 }
 
 /// Decode a JXL image to PNG (or APNG if animated)
 #[wasm_bindgen]
-pub fn decode_jxl_to_png(data: &[u8]) -> Result<Vec<u8>, JsValue> {
+pub fn decode_jxl_to_png(data: &[u8], config: Option<DecodeConfig>) -> Result<Vec<u8>, JsValue> {
     console_error_panic_hook::set_once();
-    
+
+    let config = config.unwrap_or_default();
+    let sixteen_bit = config.bit_depth == 16;
+
     if data.len() < 2 {
         return Err(JsValue::from_str("Input too small to be a JXL file"));
     }
-    
+
     let options = JxlDecoderOptions::default();
     let decoder = JxlDecoder::new(options);
     let mut input = data;
@@ -49,23 +154,45 @@ pub fn decode_jxl_to_png(data: &[u8]) -> Result<Vec<u8>, JsValue> {
     let is_animated = basic_info.animation.is_some();
     let animation_info = basic_info.animation.clone();
     
+    // Pick the color type from the stream instead of always inflating to RGBA,
+    // and honor the requested bit depth.
+    let (color_type, png_color, samples) = select_color_type(&basic_info);
+    let bytes_per_sample = if sixteen_bit { 2 } else { 1 };
+    let data_format = if sixteen_bit {
+        JxlDataFormat::U16 { bit_depth: 16 }
+    } else {
+        JxlDataFormat::U8 { bit_depth: 8 }
+    };
+
+    // Pull the embedded ICC profile so color-managed viewers don't assume sRGB.
+    let icc_profile = decoder_with_info.icc_profile();
+    let cicp = decoder_with_info.cicp();
+
+    // Preserve the EXIF box in the PNG and honor the declared orientation.
+    let exif = decoder_with_info.exif();
+    let orientation = orientation_to_exif(basic_info.orientation);
+
     // Build pixel format
     let num_extra_channels = basic_info.extra_channels.len();
     let pixel_format = JxlPixelFormat {
-        color_type: JxlColorType::Rgba,
-        color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+        color_type,
+        color_data_format: Some(data_format),
         extra_channel_format: vec![None; num_extra_channels],
     };
-    
+
     let mut decoder_with_info = decoder_with_info;
     decoder_with_info.set_pixel_format(pixel_format);
-    
-    // Collect all frames
-    let mut frames: Vec<(Vec<u8>, u32)> = Vec::new(); // (pixels, delay_ms)
-    let stride = width * 4;
-    
+
+    // Reject decompression bombs before allocating any pixel buffers.
+    checked_pixel_count(width, height, config.max_pixels as u64)?;
+
+    // Collect all frames, each with its crop rectangle and blend mode.
+    let mut frames: Vec<AnimFrame> = Vec::new();
+    let pixel_bytes = samples * bytes_per_sample;
+    let mut animation_bytes: u64 = 0;
+
     let mut current_decoder = decoder_with_info;
-    
+
     loop {
         // Advance to frame info
         let decoder_with_frame = loop {
@@ -80,7 +207,7 @@ pub fn decode_jxl_to_png(data: &[u8]) -> Result<Vec<u8>, JsValue> {
                 Err(e) => return Err(JsValue::from_str(&format!("JXL frame info error: {}", e))),
             }
         };
-        
+
         // Get frame duration if animated
         let frame_header = decoder_with_frame.frame_header();
         let delay_ms = if let Some(ref anim) = animation_info {
@@ -92,21 +219,52 @@ pub fn decode_jxl_to_png(data: &[u8]) -> Result<Vec<u8>, JsValue> {
         } else {
             0
         };
-        
-        // Allocate and decode frame
-        let mut image_buffer = Image::<u8>::new((stride, height))
+
+        // Read the frame's crop rectangle and blend mode. Frames without an
+        // explicit layer cover the full canvas and overwrite it.
+        let (fx, fy, fw, fh, blend_over) = match frame_header.layer_info {
+            Some(ref li) => (
+                li.crop_x0.max(0) as usize,
+                li.crop_y0.max(0) as usize,
+                li.xsize as usize,
+                li.ysize as usize,
+                li.blend_mode == JxlBlendMode::Blend,
+            ),
+            None => (0, 0, width, height, false),
+        };
+        let frame_stride = fw * pixel_bytes;
+
+        // Reject a frame whose crop rectangle would land outside the canvas
+        // before it ever reaches `composite_frame`.
+        validate_frame_rect(fx, fy, fw, fh, width, height)?;
+
+        // Enforce the per-frame pixel limit and the cumulative animation budget
+        // before allocating this frame's buffer.
+        let frame_pixels = checked_pixel_count(fw, fh, config.max_pixels as u64)?;
+        animation_bytes = animation_bytes
+            .checked_add(frame_pixels.saturating_mul(pixel_bytes as u64))
+            .filter(|total| *total <= config.max_animation_bytes as u64)
+            .ok_or_else(|| {
+                JsValue::from_str(&format!(
+                    "Animation exceeds memory budget ({} bytes)",
+                    config.max_animation_bytes
+                ))
+            })?;
+
+        // Allocate and decode this frame into a buffer sized to its own crop.
+        let mut image_buffer = Image::<u8>::new((frame_stride, fh))
             .map_err(|e| JsValue::from_str(&format!("Buffer alloc failed: {}", e)))?;
-        
+
         {
             let rect = Rect {
                 origin: (0, 0),
-                size: (stride, height),
+                size: (frame_stride, fh),
             };
-            
+
             let mut buffers = vec![JxlOutputBuffer::from_image_rect_mut(
                 image_buffer.get_rect_mut(rect).into_raw()
             )];
-            
+
             let mut dec3 = decoder_with_frame;
             loop {
                 match dec3.process(&mut input, &mut buffers) {
@@ -124,15 +282,29 @@ pub fn decode_jxl_to_png(data: &[u8]) -> Result<Vec<u8>, JsValue> {
                 }
             }
         }
-        
-        // Flatten to contiguous buffer
-        let mut flat_pixels = Vec::with_capacity(stride * height);
-        for y in 0..height {
+
+        // Flatten to contiguous buffer. The decoder emits native-endian samples;
+        // PNG stores 16-bit samples big-endian, so swap each pair when needed.
+        let mut flat_pixels = Vec::with_capacity(frame_stride * fh);
+        for y in 0..fh {
             flat_pixels.extend_from_slice(image_buffer.row(y));
         }
-        
-        frames.push((flat_pixels, delay_ms.max(10))); // min 10ms delay
-        
+        if sixteen_bit && cfg!(target_endian = "little") {
+            for pair in flat_pixels.chunks_exact_mut(2) {
+                pair.swap(0, 1);
+            }
+        }
+
+        frames.push(AnimFrame {
+            pixels: flat_pixels,
+            delay_ms: delay_ms.max(10), // min 10ms delay
+            x0: fx,
+            y0: fy,
+            width: fw,
+            height: fh,
+            blend_over,
+        });
+
         // Check for more frames
         if !current_decoder.has_more_frames() {
             break;
@@ -140,55 +312,696 @@ pub fn decode_jxl_to_png(data: &[u8]) -> Result<Vec<u8>, JsValue> {
     }
     
     // Encode output
+    let bit_depth = if sixteen_bit {
+        png::BitDepth::Sixteen
+    } else {
+        png::BitDepth::Eight
+    };
     if frames.len() == 1 || !is_animated {
-        // Static PNG
-        encode_static_png(width, height, &frames[0].0)
+        // Static PNG. Composite the single frame onto a full-canvas buffer
+        // first: a still frame may carry a sub-canvas crop, so orienting its
+        // own (smaller) crop buffer against the canvas dimensions would index
+        // past its end. Then bake the declared orientation into the pixels.
+        let mut canvas = vec![0u8; width * height * pixel_bytes];
+        composite_frame(&mut canvas, width, &frames[0], png_color, bytes_per_sample, false);
+        let (pixels, out_w, out_h) =
+            apply_orientation(&canvas, width, height, pixel_bytes, orientation);
+        // The orientation is now baked into the pixels, so force the emitted
+        // EXIF Orientation tag to 1; otherwise an `eXIf`-aware viewer would
+        // rotate the already-rotated image a second time.
+        let exif = exif.as_deref().map(normalize_exif_orientation);
+        encode_static_png(
+            out_w,
+            out_h,
+            &pixels,
+            png_color,
+            bit_depth,
+            icc_profile.as_deref(),
+            cicp,
+            exif.as_deref(),
+        )
     } else {
-        // Animated PNG (APNG)
-        encode_apng(width, height, &frames)
+        // Animated PNG (APNG). Orientation can't be represented per-frame in
+        // APNG, so it is baked into every composited frame instead, and the
+        // EXIF Orientation tag is normalized to 1 for the same reason as the
+        // static path above. ICC/cICP are passed through unchanged: color
+        // management applies to the whole stream, not any one frame.
+        let exif = exif.as_deref().map(normalize_exif_orientation);
+        encode_apng(
+            width,
+            height,
+            &frames,
+            png_color,
+            bit_depth,
+            orientation,
+            exif.as_deref(),
+            icc_profile.as_deref(),
+            cicp,
+        )
     }
 }
 
-fn encode_static_png(width: usize, height: usize, pixels: &[u8]) -> Result<Vec<u8>, JsValue> {
-    use image::ImageEncoder;
-    
-    let mut png_data = Vec::new();
-    let encoder = image::codecs::png::PngEncoder::new(&mut png_data);
-    encoder.write_image(
-        pixels,
-        width as u32,
-        height as u32,
-        image::ColorType::Rgba8
-    ).map_err(|e| JsValue::from_str(&format!("PNG encode error: {}", e)))?;
-    
-    Ok(png_data)
+/// A decoded animation frame and its placement on the canvas.
+struct AnimFrame {
+    /// Flattened sample bytes for the frame's crop rectangle.
+    pixels: Vec<u8>,
+    /// Display duration in milliseconds.
+    delay_ms: u32,
+    /// Top-left of the crop rectangle within the canvas.
+    x0: usize,
+    y0: usize,
+    /// Crop rectangle dimensions.
+    width: usize,
+    height: usize,
+    /// `true` when the frame alpha-blends over the canvas, `false` to overwrite.
+    blend_over: bool,
+}
+
+/// Reconstruct the original JPEG bytes from a losslessly transcoded JXL.
+///
+/// Many JXL files are JPEGs that were transcoded without generational loss and
+/// carry a reconstruction box. For those streams the decoder can rebuild the
+/// exact original JPEG instead of decoding to pixels. Returns a descriptive
+/// error when the stream is not reconstructible so callers can fall back to
+/// `decode_jxl_to_png`.
+#[wasm_bindgen]
+pub fn decode_jxl_to_jpeg(data: &[u8], config: Option<DecodeConfig>) -> Result<Vec<u8>, JsValue> {
+    console_error_panic_hook::set_once();
+
+    let config = config.unwrap_or_default();
+
+    if data.len() < 2 {
+        return Err(JsValue::from_str("Input too small to be a JXL file"));
+    }
+
+    let mut options = JxlDecoderOptions::default();
+    options.reconstruct_jpeg = true;
+    let decoder = JxlDecoder::new(options);
+    let mut input = data;
+
+    // Advance to image info
+    let mut dec = decoder;
+    let decoder_with_info = loop {
+        match dec.process(&mut input) {
+            Ok(ProcessingResult::Complete { result }) => break result,
+            Ok(ProcessingResult::NeedsMoreInput { fallback, .. }) => {
+                if input.is_empty() {
+                    return Err(JsValue::from_str("Incomplete JXL data (header)"));
+                }
+                dec = fallback;
+            }
+            Err(e) => return Err(JsValue::from_str(&format!("JXL header error: {}", e))),
+        }
+    };
+
+    // Reject decompression bombs from a crafted header before doing any work.
+    let (width, height) = decoder_with_info.basic_info().size;
+    checked_pixel_count(width, height, config.max_pixels as u64)?;
+
+    // A reconstruction box is required to rebuild the original JPEG bytes.
+    if !decoder_with_info.basic_info().has_jpeg_reconstruction {
+        return Err(JsValue::from_str(
+            "JXL stream has no JPEG reconstruction data (not a transcoded JPEG)",
+        ));
+    }
+
+    // Feed the stream through the reconstruction path, which writes the original
+    // JPEG bytes into a growable sink as the reconstruction box and frame data
+    // are consumed.
+    let mut jpeg = Vec::new();
+    let mut current_decoder = decoder_with_info;
+    loop {
+        match current_decoder.process_jpeg(&mut input, &mut jpeg) {
+            Ok(ProcessingResult::Complete { .. }) => break,
+            Ok(ProcessingResult::NeedsMoreInput { fallback, .. }) => {
+                if input.is_empty() {
+                    return Err(JsValue::from_str("Incomplete JXL data (jpeg reconstruction)"));
+                }
+                current_decoder = fallback;
+            }
+            Err(e) => {
+                return Err(JsValue::from_str(&format!(
+                    "JXL JPEG reconstruction error: {}",
+                    e
+                )))
+            }
+        }
+    }
+
+    Ok(jpeg)
+}
+
+/// Progressively decode a JXL, invoking `on_pass` with an RGBA PNG after each
+/// progressive pass so a browser can render a blurry-to-sharp preview while
+/// bytes are still arriving.
+///
+/// The callback is called as `on_pass(pass_index, width, height, png_bytes)`
+/// for every pass, including the final one.
+#[wasm_bindgen]
+pub fn decode_jxl_progressive(
+    data: &[u8],
+    on_pass: js_sys::Function,
+    config: Option<DecodeConfig>,
+) -> Result<(), JsValue> {
+    console_error_panic_hook::set_once();
+
+    let config = config.unwrap_or_default();
+
+    if data.len() < 2 {
+        return Err(JsValue::from_str("Input too small to be a JXL file"));
+    }
+
+    let mut options = JxlDecoderOptions::default();
+    options.progressive_detail = true;
+    let decoder = JxlDecoder::new(options);
+    let mut input = data;
+
+    // Advance to image info
+    let mut dec = decoder;
+    let decoder_with_info = loop {
+        match dec.process(&mut input) {
+            Ok(ProcessingResult::Complete { result }) => break result,
+            Ok(ProcessingResult::NeedsMoreInput { fallback, .. }) => {
+                if input.is_empty() {
+                    return Err(JsValue::from_str("Incomplete JXL data (header)"));
+                }
+                dec = fallback;
+            }
+            Err(e) => return Err(JsValue::from_str(&format!("JXL header error: {}", e))),
+        }
+    };
+
+    let basic_info = decoder_with_info.basic_info().clone();
+    let (width, height) = basic_info.size;
+
+    if width == 0 || height == 0 {
+        return Err(JsValue::from_str("Invalid image dimensions"));
+    }
+
+    // Reject decompression bombs before allocating the preview buffer.
+    checked_pixel_count(width, height, config.max_pixels as u64)?;
+
+    // Preview passes are always rendered as 8-bit RGBA; refinement only sharpens.
+    let num_extra_channels = basic_info.extra_channels.len();
+    let pixel_format = JxlPixelFormat {
+        color_type: JxlColorType::Rgba,
+        color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+        extra_channel_format: vec![None; num_extra_channels],
+    };
+
+    let mut decoder_with_info = decoder_with_info;
+    decoder_with_info.set_pixel_format(pixel_format);
+
+    let stride = width * 4;
+
+    // Advance to frame info (previews are rendered from the first frame).
+    let mut current_decoder = decoder_with_info;
+    let decoder_with_frame = loop {
+        match current_decoder.process(&mut input) {
+            Ok(ProcessingResult::Complete { result }) => break result,
+            Ok(ProcessingResult::NeedsMoreInput { fallback, .. }) => {
+                if input.is_empty() {
+                    return Err(JsValue::from_str("Incomplete JXL data (frame info)"));
+                }
+                current_decoder = fallback;
+            }
+            Err(e) => return Err(JsValue::from_str(&format!("JXL frame info error: {}", e))),
+        }
+    };
+
+    let mut image_buffer = Image::<u8>::new((stride, height))
+        .map_err(|e| JsValue::from_str(&format!("Buffer alloc failed: {}", e)))?;
+
+    let rect = Rect {
+        origin: (0, 0),
+        size: (stride, height),
+    };
+    let mut buffers = vec![JxlOutputBuffer::from_image_rect_mut(
+        image_buffer.get_rect_mut(rect).into_raw(),
+    )];
+
+    let mut pass_index = 0u32;
+    let mut dec3 = decoder_with_frame;
+    loop {
+        match dec3.process(&mut input, &mut buffers) {
+            Ok(ProcessingResult::Complete { .. }) => {
+                emit_pass(&on_pass, pass_index, width, height, &image_buffer, stride)?;
+                break;
+            }
+            Ok(ProcessingResult::NeedsMoreInput { fallback, .. }) => {
+                // A progressive pass has been flushed into the buffers; hand the
+                // current (partial) image to the caller before continuing.
+                emit_pass(&on_pass, pass_index, width, height, &image_buffer, stride)?;
+                pass_index += 1;
+                if input.is_empty() {
+                    return Err(JsValue::from_str("Incomplete JXL data (pixels)"));
+                }
+                dec3 = fallback;
+            }
+            Err(e) => return Err(JsValue::from_str(&format!("JXL decode error: {}", e))),
+        }
+    }
+
+    Ok(())
+}
+
+/// Encode the current image buffer as RGBA PNG and invoke the JS pass callback.
+fn emit_pass(
+    on_pass: &js_sys::Function,
+    pass_index: u32,
+    width: usize,
+    height: usize,
+    image_buffer: &Image<u8>,
+    stride: usize,
+) -> Result<(), JsValue> {
+    let mut flat_pixels = Vec::with_capacity(stride * height);
+    for y in 0..height {
+        flat_pixels.extend_from_slice(image_buffer.row(y));
+    }
+
+    let png = encode_static_png(
+        width,
+        height,
+        &flat_pixels,
+        png::ColorType::Rgba,
+        png::BitDepth::Eight,
+        None,
+        None,
+        None,
+    )?;
+
+    let args = js_sys::Array::new();
+    args.push(&JsValue::from(pass_index));
+    args.push(&JsValue::from(width as u32));
+    args.push(&JsValue::from(height as u32));
+    args.push(&JsValue::from(js_sys::Uint8Array::from(&png[..])));
+    on_pass.apply(&JsValue::NULL, &args)?;
+
+    Ok(())
 }
 
-fn encode_apng(width: usize, height: usize, frames: &[(Vec<u8>, u32)]) -> Result<Vec<u8>, JsValue> {
+/// Map the jxl orientation field to its EXIF orientation value (1..=8).
+fn orientation_to_exif(orientation: JxlOrientation) -> u8 {
+    match orientation {
+        JxlOrientation::Identity => 1,
+        JxlOrientation::FlipHorizontal => 2,
+        JxlOrientation::Rotate180 => 3,
+        JxlOrientation::FlipVertical => 4,
+        JxlOrientation::Transpose => 5,
+        JxlOrientation::Rotate90 => 6,
+        JxlOrientation::AntiTranspose => 7,
+        JxlOrientation::Rotate270 => 8,
+    }
+}
+
+/// Apply an EXIF orientation to a flattened sample buffer, returning the
+/// transformed pixels and the (possibly swapped) output dimensions.
+fn apply_orientation(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+    orientation: u8,
+) -> (Vec<u8>, usize, usize) {
+    if orientation <= 1 {
+        return (pixels.to_vec(), width, height);
+    }
+
+    // Orientations 5..=8 transpose the axes and therefore swap the dimensions.
+    let swaps_axes = matches!(orientation, 5 | 6 | 7 | 8);
+    let (out_w, out_h) = if swaps_axes {
+        (height, width)
+    } else {
+        (width, height)
+    };
+
+    let mut out = vec![0u8; out_w * out_h * pixel_bytes];
+    for y in 0..height {
+        for x in 0..width {
+            // Destination coordinate for source (x, y) under each orientation.
+            let (dx, dy) = match orientation {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (height - 1 - y, x),
+                7 => (height - 1 - y, width - 1 - x),
+                8 => (y, width - 1 - x),
+                _ => (x, y),
+            };
+            let src = (y * width + x) * pixel_bytes;
+            let dst = (dy * out_w + dx) * pixel_bytes;
+            out[dst..dst + pixel_bytes].copy_from_slice(&pixels[src..src + pixel_bytes]);
+        }
+    }
+
+    (out, out_w, out_h)
+}
+
+/// Force the EXIF Orientation tag (0x0112) in a raw EXIF/TIFF block to 1
+/// ("top-left"). Called after the orientation has been baked into the pixels so
+/// a viewer that honors the PNG `eXIf` chunk does not rotate the image again.
+/// The block is returned unchanged when no TIFF header or Orientation tag is
+/// found.
+fn normalize_exif_orientation(exif: &[u8]) -> Vec<u8> {
+    let mut out = exif.to_vec();
+
+    // The TIFF header ("II" little-endian or "MM" big-endian) may be preceded
+    // by a short offset prefix in the JXL Exif box; scan the first few bytes.
+    let base = match out
+        .windows(4)
+        .take(16)
+        .position(|w| w == [0x49, 0x49, 0x2A, 0x00] || w == [0x4D, 0x4D, 0x00, 0x2A])
+    {
+        Some(off) => off,
+        None => return out,
+    };
+
+    let little_endian = out[base] == 0x49;
+    let u16_at = |b: &[u8], i: usize| -> u16 {
+        if little_endian {
+            u16::from_le_bytes([b[i], b[i + 1]])
+        } else {
+            u16::from_be_bytes([b[i], b[i + 1]])
+        }
+    };
+    let u32_at = |b: &[u8], i: usize| -> u32 {
+        if little_endian {
+            u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+        } else {
+            u32::from_be_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+        }
+    };
+
+    // IFD0 offset is relative to the TIFF header start.
+    if base + 8 > out.len() {
+        return out;
+    }
+    let ifd0 = base + u32_at(&out, base + 4) as usize;
+    if ifd0 + 2 > out.len() {
+        return out;
+    }
+    let entry_count = u16_at(&out, ifd0) as usize;
+    for e in 0..entry_count {
+        let entry = ifd0 + 2 + e * 12;
+        if entry + 12 > out.len() {
+            break;
+        }
+        if u16_at(&out, entry) == 0x0112 {
+            // Orientation is a SHORT stored inline in the value/offset field;
+            // write 1 in the block's byte order.
+            let val = entry + 8;
+            let one = if little_endian { [1, 0] } else { [0, 1] };
+            out[val] = one[0];
+            out[val + 1] = one[1];
+            break;
+        }
+    }
+
+    out
+}
+
+fn encode_static_png(
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+    color: png::ColorType,
+    depth: png::BitDepth,
+    icc: Option<&[u8]>,
+    cicp: Option<[u8; 4]>,
+    exif: Option<&[u8]>,
+) -> Result<Vec<u8>, JsValue> {
     let mut output = Vec::new();
-    
+
     {
         let mut encoder = png::Encoder::new(&mut output, width as u32, height as u32);
-        encoder.set_color(png::ColorType::Rgba);
-        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        if let Some(profile) = icc {
+            encoder
+                .set_icc_profile(std::borrow::Cow::Owned(profile.to_vec()))
+                .map_err(|e| JsValue::from_str(&format!("iCCP error: {}", e)))?;
+        }
+
+        let mut writer = encoder
+            .write_header()
+            .map_err(|e| JsValue::from_str(&format!("PNG header error: {}", e)))?;
+
+        // cICP carries the coding-independent code points when the color
+        // encoding maps onto a standard primaries/transfer combination.
+        if let Some(c) = cicp {
+            writer
+                .write_chunk(png::chunk::ChunkType(*b"cICP"), &c)
+                .map_err(|e| JsValue::from_str(&format!("cICP error: {}", e)))?;
+        }
+
+        // Carry the original EXIF block so orientation/camera tags survive.
+        if let Some(bytes) = exif {
+            writer
+                .write_chunk(png::chunk::ChunkType(*b"eXIf"), bytes)
+                .map_err(|e| JsValue::from_str(&format!("eXIf error: {}", e)))?;
+        }
+
+        writer
+            .write_image_data(pixels)
+            .map_err(|e| JsValue::from_str(&format!("PNG encode error: {}", e)))?;
+        writer
+            .finish()
+            .map_err(|e| JsValue::from_str(&format!("PNG finish error: {}", e)))?;
+    }
+
+    Ok(output)
+}
+
+fn encode_apng(
+    width: usize,
+    height: usize,
+    frames: &[AnimFrame],
+    color: png::ColorType,
+    depth: png::BitDepth,
+    orientation: u8,
+    exif: Option<&[u8]>,
+    icc: Option<&[u8]>,
+    cicp: Option<[u8; 4]>,
+) -> Result<Vec<u8>, JsValue> {
+    let samples = png_samples(color);
+    let bytes_per_sample = if depth == png::BitDepth::Sixteen { 2 } else { 1 };
+    let pixel_bytes = samples * bytes_per_sample;
+    let has_alpha = matches!(color, png::ColorType::Rgba | png::ColorType::GrayscaleAlpha);
+
+    // Orientation rotates/transposes every frame identically, so the header
+    // dimensions (and the buffer used to diff consecutive frames) must use
+    // the oriented size, not the decoded canvas size.
+    let swaps_axes = matches!(orientation, 5 | 6 | 7 | 8);
+    let (out_w, out_h) = if swaps_axes { (height, width) } else { (width, height) };
+
+    // Persistent canvas holding the fully-composited previous frame, kept in
+    // decoded (pre-orientation) space so blending matches the source layout.
+    let mut canvas = vec![0u8; width * height * pixel_bytes];
+    // The same frame after orientation, kept only to diff against the next
+    // oriented frame and find the minimal changed region to emit.
+    let mut oriented_prev = vec![0u8; out_w * out_h * pixel_bytes];
+
+    let mut output = Vec::new();
+
+    {
+        let mut encoder = png::Encoder::new(&mut output, out_w as u32, out_h as u32);
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        if let Some(profile) = icc {
+            encoder
+                .set_icc_profile(std::borrow::Cow::Owned(profile.to_vec()))
+                .map_err(|e| JsValue::from_str(&format!("iCCP error: {}", e)))?;
+        }
         encoder.set_animated(frames.len() as u32, 0).map_err(|e| JsValue::from_str(&format!("APNG setup error: {}", e)))?;
-        
+
         let mut writer = encoder.write_header().map_err(|e| JsValue::from_str(&format!("PNG header error: {}", e)))?;
-        
-        for (i, (pixels, delay_ms)) in frames.iter().enumerate() {
-            // Set frame delay: delay_ms milliseconds = delay_ms/1000 seconds
-            // png crate uses num/den format
-            writer.set_frame_delay(*delay_ms as u16, 1000).map_err(|e| JsValue::from_str(&format!("Frame delay error: {}", e)))?;
-            
-            writer.write_image_data(pixels).map_err(|e| JsValue::from_str(&format!("Frame {} write error: {}", i, e)))?;
-        }
-        
+
+        // cICP carries the coding-independent code points when the color
+        // encoding maps onto a standard primaries/transfer combination.
+        if let Some(c) = cicp {
+            writer
+                .write_chunk(png::chunk::ChunkType(*b"cICP"), &c)
+                .map_err(|e| JsValue::from_str(&format!("cICP error: {}", e)))?;
+        }
+
+        // Carry the original EXIF block (with Orientation normalized to 1 by
+        // the caller, since the rotation is already baked into every frame).
+        if let Some(bytes) = exif {
+            writer
+                .write_chunk(png::chunk::ChunkType(*b"eXIf"), bytes)
+                .map_err(|e| JsValue::from_str(&format!("eXIf error: {}", e)))?;
+        }
+
+        for (i, frame) in frames.iter().enumerate() {
+            // Composite the frame into a fresh copy of the canvas, in decoded
+            // space, then bake in the orientation before diffing/emitting.
+            let mut next = canvas.clone();
+            composite_frame(
+                &mut next,
+                width,
+                frame,
+                color,
+                bytes_per_sample,
+                has_alpha && frame.blend_over,
+            );
+            let (oriented_next, _, _) = apply_orientation(&next, width, height, pixel_bytes, orientation);
+
+            let (rx, ry, rw, rh) =
+                changed_bounds(&oriented_prev, &oriented_next, out_w, out_h, pixel_bytes)
+                    // An identical frame still needs a 1x1 region to carry its delay.
+                    .unwrap_or((0, 0, 1, 1));
+
+            // Extract the sub-rectangle of the oriented, composited canvas.
+            let mut region = Vec::with_capacity(rw * rh * pixel_bytes);
+            for y in ry..ry + rh {
+                let row = y * out_w * pixel_bytes;
+                region.extend_from_slice(&oriented_next[row + rx * pixel_bytes..row + (rx + rw) * pixel_bytes]);
+            }
+
+            writer
+                .set_frame_position(rx as u32, ry as u32, rw as u32, rh as u32)
+                .map_err(|e| JsValue::from_str(&format!("Frame position error: {}", e)))?;
+            // The emitted pixels are already composited, so overwrite the region.
+            writer
+                .set_blend_op(png::BlendOp::Source)
+                .map_err(|e| JsValue::from_str(&format!("Blend op error: {}", e)))?;
+            writer
+                .set_dispose_op(png::DisposeOp::None)
+                .map_err(|e| JsValue::from_str(&format!("Dispose op error: {}", e)))?;
+            // Set frame delay: delay_ms milliseconds = delay_ms/1000 seconds.
+            // png crate uses num/den format.
+            writer
+                .set_frame_delay(frame.delay_ms as u16, 1000)
+                .map_err(|e| JsValue::from_str(&format!("Frame delay error: {}", e)))?;
+
+            writer
+                .write_image_data(&region)
+                .map_err(|e| JsValue::from_str(&format!("Frame {} write error: {}", i, e)))?;
+
+            canvas = next;
+            oriented_prev = oriented_next;
+        }
+
         writer.finish().map_err(|e| JsValue::from_str(&format!("APNG finish error: {}", e)))?;
     }
-    
+
     Ok(output)
 }
 
+/// Samples per pixel for a PNG color type.
+fn png_samples(color: png::ColorType) -> usize {
+    match color {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        png::ColorType::Rgba => 4,
+        png::ColorType::Indexed => 1,
+    }
+}
+
+/// Composite a frame's crop rectangle onto the canvas. With `alpha_blend` the
+/// frame is drawn OVER the canvas using its alpha channel; otherwise it
+/// overwrites the sub-rectangle (SOURCE).
+///
+/// The blend path is keyed on the actual color type and 8-bit sample size, not
+/// the raw byte width: a 16-bit Grayscale+Alpha frame is also 4 bytes/pixel but
+/// must not be read as 8-bit RGBA, so it falls through to an overwrite.
+fn composite_frame(
+    canvas: &mut [u8],
+    canvas_width: usize,
+    frame: &AnimFrame,
+    color: png::ColorType,
+    bytes_per_sample: usize,
+    alpha_blend: bool,
+) {
+    let pixel_bytes = png_samples(color) * bytes_per_sample;
+    let frame_stride = frame.width * pixel_bytes;
+
+    // Alpha math below assumes 8-bit samples; only RGBA / GrayscaleAlpha at one
+    // byte per sample blend, everything else overwrites.
+    let blend_samples = if alpha_blend && bytes_per_sample == 1 {
+        match color {
+            png::ColorType::Rgba => Some(4usize),
+            png::ColorType::GrayscaleAlpha => Some(2usize),
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    for row in 0..frame.height {
+        let cy = frame.y0 + row;
+        let dst_row = (cy * canvas_width + frame.x0) * pixel_bytes;
+        let src_row = row * frame_stride;
+        match blend_samples {
+            Some(4) => {
+                // 8-bit RGBA, straight (non-premultiplied) alpha:
+                // color = (src * srcA + dst * (1 - srcA)), alpha = srcA over dstA.
+                for px in 0..frame.width {
+                    let s = src_row + px * 4;
+                    let d = dst_row + px * 4;
+                    let sa = frame.pixels[s + 3] as u32;
+                    for c in 0..3 {
+                        let sv = frame.pixels[s + c] as u32;
+                        let dv = canvas[d + c] as u32;
+                        canvas[d + c] = ((sv * sa + dv * (255 - sa)) / 255) as u8;
+                    }
+                    let da = canvas[d + 3] as u32;
+                    canvas[d + 3] = (sa + da * (255 - sa) / 255) as u8;
+                }
+            }
+            Some(2) => {
+                // 8-bit grayscale + alpha.
+                for px in 0..frame.width {
+                    let s = src_row + px * 2;
+                    let d = dst_row + px * 2;
+                    let sa = frame.pixels[s + 1] as u32;
+                    let sv = frame.pixels[s] as u32;
+                    let dv = canvas[d] as u32;
+                    canvas[d] = ((sv * sa + dv * (255 - sa)) / 255) as u8;
+                    canvas[d + 1] = (sa + (canvas[d + 1] as u32) * (255 - sa) / 255) as u8;
+                }
+            }
+            _ => {
+                let width_bytes = frame.width * pixel_bytes;
+                canvas[dst_row..dst_row + width_bytes]
+                    .copy_from_slice(&frame.pixels[src_row..src_row + width_bytes]);
+            }
+        }
+    }
+}
+
+/// Bounding box `(x, y, w, h)` of pixels that differ between two canvases, or
+/// `None` when they are identical.
+fn changed_bounds(
+    prev: &[u8],
+    next: &[u8],
+    width: usize,
+    height: usize,
+    pixel_bytes: usize,
+) -> Option<(usize, usize, usize, usize)> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (width, height, 0usize, 0usize);
+    let mut any = false;
+    for y in 0..height {
+        for x in 0..width {
+            let off = (y * width + x) * pixel_bytes;
+            if prev[off..off + pixel_bytes] != next[off..off + pixel_bytes] {
+                any = true;
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x);
+                max_y = max_y.max(y);
+            }
+        }
+    }
+    if any {
+        Some((min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+    } else {
+        None
+    }
+}
+
 #[wasm_bindgen]
 pub fn get_jxl_info(data: &[u8]) -> Result<JxlInfo, JsValue> {
     console_error_panic_hook::set_once();
@@ -216,13 +1029,320 @@ pub fn get_jxl_info(data: &[u8]) -> Result<JxlInfo, JsValue> {
     };
     
     let info = decoder_with_info.basic_info();
-    let has_alpha = !info.extra_channels.is_empty();
-    let num_frames = if info.animation.is_some() { 2 } else { 1 }; // Approximate
-    
+    // Inspect extra-channel types instead of assuming any extra channel is alpha.
+    let has_alpha = has_alpha_channel(info);
+    let (width, height) = info.size;
+
+    let (num_loops, tps_numerator, tps_denominator) = match info.animation {
+        Some(ref anim) => (anim.num_loops, anim.tps_numerator, anim.tps_denominator),
+        None => (0, 0, 0),
+    };
+    let is_animated = info.animation.is_some();
+    let num_extra_channels = info.extra_channels.len();
+
+    // Prefer an exact frame count from the decoder. When it can't report one
+    // without fully demuxing the stream, fall back to counting frames
+    // ourselves by walking frame headers one at a time; only when that also
+    // fails to reach the end of the stream do we give up with a lower-bound
+    // estimate (flagged via `frame_count_is_exact`).
+    let (num_frames, frame_count_is_exact) = match decoder_with_info.frame_count() {
+        Some(n) => (n, true),
+        None if is_animated => {
+            // The decoder has no lighter-weight "skip frame" primitive, so
+            // each frame's pixels are decoded into a throwaway single-channel
+            // buffer just to walk past it; this only runs when `frame_count`
+            // itself can't answer cheaply. `get_jxl_info` takes no
+            // `DecodeConfig`, so fall back on the same default pixel/memory
+            // budget `DecodeConfig` uses elsewhere rather than decoding
+            // unbounded attacker-controlled frame sizes: a frame or a running
+            // total that would exceed it just gives up on the exact count.
+            let bomb_guard = DecodeConfig::default();
+            let mut decoder_with_info = decoder_with_info;
+            decoder_with_info.set_pixel_format(JxlPixelFormat {
+                color_type: JxlColorType::Grayscale,
+                color_data_format: Some(JxlDataFormat::U8 { bit_depth: 8 }),
+                extra_channel_format: vec![None; num_extra_channels],
+            });
+
+            let mut current_decoder = decoder_with_info;
+            let mut count = 0usize;
+            let mut counted_bytes: u64 = 0;
+            let counted = 'count: loop {
+                let decoder_with_frame = loop {
+                    match current_decoder.process(&mut input) {
+                        Ok(ProcessingResult::Complete { result }) => break result,
+                        Ok(ProcessingResult::NeedsMoreInput { fallback, .. }) => {
+                            if input.is_empty() {
+                                break 'count None;
+                            }
+                            current_decoder = fallback;
+                        }
+                        Err(_) => break 'count None,
+                    }
+                };
+
+                let frame_header = decoder_with_frame.frame_header();
+                let (fw, fh) = match frame_header.layer_info {
+                    Some(ref li) => (li.xsize as usize, li.ysize as usize),
+                    None => (width, height),
+                };
+
+                let frame_pixels = match checked_pixel_count(fw, fh, bomb_guard.max_pixels as u64) {
+                    Ok(n) => n,
+                    Err(_) => break 'count None,
+                };
+                counted_bytes = match counted_bytes
+                    .checked_add(frame_pixels)
+                    .filter(|total| *total <= bomb_guard.max_animation_bytes as u64)
+                {
+                    Some(total) => total,
+                    None => break 'count None,
+                };
+
+                let mut image_buffer = match Image::<u8>::new((fw, fh)) {
+                    Ok(buf) => buf,
+                    Err(_) => break 'count None,
+                };
+                let rect = Rect {
+                    origin: (0, 0),
+                    size: (fw, fh),
+                };
+                let mut buffers = vec![JxlOutputBuffer::from_image_rect_mut(
+                    image_buffer.get_rect_mut(rect).into_raw(),
+                )];
+
+                let mut dec3 = decoder_with_frame;
+                current_decoder = loop {
+                    match dec3.process(&mut input, &mut buffers) {
+                        Ok(ProcessingResult::Complete { result }) => break result,
+                        Ok(ProcessingResult::NeedsMoreInput { fallback, .. }) => {
+                            if input.is_empty() {
+                                break 'count None;
+                            }
+                            dec3 = fallback;
+                        }
+                        Err(_) => break 'count None,
+                    }
+                };
+
+                count += 1;
+                if !current_decoder.has_more_frames() {
+                    break 'count Some(count);
+                }
+            };
+
+            match counted {
+                Some(n) => (n, true),
+                None => (1, false),
+            }
+        }
+        None => (1, true),
+    };
+
     Ok(JxlInfo {
-        width: info.size.0 as u32,
-        height: info.size.1 as u32,
+        width: width as u32,
+        height: height as u32,
         num_frames,
+        frame_count_is_exact,
         has_alpha,
+        num_loops,
+        tps_numerator,
+        tps_denominator,
     })
 }
+
+/// Extract the EXIF and XMP metadata boxes the decoder surfaces, returning a
+/// `{ exif, xmp }` object whose fields are `Uint8Array`s (or `null` when the
+/// box is absent).
+#[wasm_bindgen]
+pub fn get_jxl_metadata(data: &[u8]) -> Result<JsValue, JsValue> {
+    console_error_panic_hook::set_once();
+
+    if data.len() < 2 {
+        return Err(JsValue::from_str("Input too small"));
+    }
+
+    let options = JxlDecoderOptions::default();
+    let decoder = JxlDecoder::new(options);
+    let mut input = data;
+
+    let mut dec = decoder;
+    let decoder_with_info = loop {
+        match dec.process(&mut input) {
+            Ok(ProcessingResult::Complete { result }) => break result,
+            Ok(ProcessingResult::NeedsMoreInput { fallback, .. }) => {
+                if input.is_empty() {
+                    return Err(JsValue::from_str("Incomplete JXL data"));
+                }
+                dec = fallback;
+            }
+            Err(e) => return Err(JsValue::from_str(&format!("JXL parse error: {}", e))),
+        }
+    };
+
+    let to_value = |box_bytes: Option<Vec<u8>>| match box_bytes {
+        Some(bytes) => JsValue::from(js_sys::Uint8Array::from(&bytes[..])),
+        None => JsValue::NULL,
+    };
+
+    let metadata = js_sys::Object::new();
+    js_sys::Reflect::set(
+        &metadata,
+        &JsValue::from_str("exif"),
+        &to_value(decoder_with_info.exif()),
+    )?;
+    js_sys::Reflect::set(
+        &metadata,
+        &JsValue::from_str("xmp"),
+        &to_value(decoder_with_info.xmp()),
+    )?;
+
+    Ok(metadata.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(pixels: Vec<u8>, x0: usize, y0: usize, width: usize, height: usize) -> AnimFrame {
+        AnimFrame {
+            pixels,
+            delay_ms: 10,
+            x0,
+            y0,
+            width,
+            height,
+            blend_over: false,
+        }
+    }
+
+    #[test]
+    fn composite_overwrite_places_crop_at_offset() {
+        let mut canvas = vec![0u8; 2 * 2 * 4];
+        let f = frame(vec![1, 2, 3, 4], 1, 1, 1, 1);
+        composite_frame(&mut canvas, 2, &f, png::ColorType::Rgba, 1, false);
+        // Only the bottom-right pixel is written.
+        assert_eq!(&canvas[12..16], &[1, 2, 3, 4]);
+        assert_eq!(&canvas[0..12], &[0; 12]);
+    }
+
+    #[test]
+    fn composite_rgba_alpha_blend() {
+        // Opaque red over white stays red; fully transparent leaves the canvas.
+        let mut canvas = vec![255u8; 2 * 4];
+        let f = frame(vec![255, 0, 0, 255, 9, 9, 9, 0], 0, 0, 2, 1);
+        composite_frame(&mut canvas, 2, &f, png::ColorType::Rgba, 1, true);
+        assert_eq!(&canvas[0..4], &[255, 0, 0, 255]);
+        assert_eq!(&canvas[4..8], &[255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn composite_rgba_partial_alpha_blend() {
+        // gray=50, alpha=128 over opaque white: color = (50*128 + 255*127)/255,
+        // alpha = srcA over dstA = (128 + 255*127/255) = 255 (dst already opaque).
+        let mut canvas = vec![255u8; 4];
+        let f = frame(vec![50, 50, 50, 128], 0, 0, 1, 1);
+        composite_frame(&mut canvas, 1, &f, png::ColorType::Rgba, 1, true);
+        assert_eq!(&canvas[0..4], &[152, 152, 152, 255]);
+    }
+
+    #[test]
+    fn composite_16bit_gray_alpha_overwrites_not_blends() {
+        // 16-bit Grayscale+Alpha is 4 bytes/pixel like 8-bit RGBA; it must be
+        // overwritten, never mis-blended as RGBA.
+        let mut canvas = vec![0u8; 4];
+        let f = frame(vec![0x12, 0x34, 0x00, 0x00], 0, 0, 1, 1);
+        composite_frame(&mut canvas, 1, &f, png::ColorType::GrayscaleAlpha, 2, true);
+        assert_eq!(&canvas[..], &[0x12, 0x34, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn validate_frame_rect_rejects_out_of_canvas_crop() {
+        // Flush against the canvas edge: fine.
+        assert!(validate_frame_rect(2, 2, 2, 2, 4, 4).is_ok());
+        // Crop extends past the right edge.
+        assert!(validate_frame_rect(3, 0, 2, 2, 4, 4).is_err());
+        // Crop extends past the bottom edge.
+        assert!(validate_frame_rect(0, 3, 2, 2, 4, 4).is_err());
+        // Overflowing x0 + width must not wrap around and pass.
+        assert!(validate_frame_rect(usize::MAX, 0, 2, 2, 4, 4).is_err());
+    }
+
+    #[test]
+    fn checked_pixel_count_limits_and_overflow() {
+        // Within the limit: returns the product.
+        assert_eq!(checked_pixel_count(10, 20, 1000).unwrap(), 200);
+        // Over the limit: rejected.
+        assert!(checked_pixel_count(100, 100, 1000).is_err());
+        // width * height overflows usize: rejected rather than wrapping.
+        assert!(checked_pixel_count(usize::MAX, 2, u64::MAX).is_err());
+    }
+
+    #[test]
+    fn orientation_identity_is_passthrough() {
+        let px = vec![1, 2, 3, 4, 5, 6];
+        let (out, w, h) = apply_orientation(&px, 3, 2, 1, 1);
+        assert_eq!((w, h), (3, 2));
+        assert_eq!(out, px);
+    }
+
+    #[test]
+    fn orientation_rotate180_reverses() {
+        // 2x2, 1 byte/pixel: rows [1,2] / [3,4] -> [4,3] / [2,1].
+        let px = vec![1, 2, 3, 4];
+        let (out, w, h) = apply_orientation(&px, 2, 2, 1, 3);
+        assert_eq!((w, h), (2, 2));
+        assert_eq!(out, vec![4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn orientation_rotate90_swaps_dimensions() {
+        // 3x2 source, orientation 6 (Rotate90) swaps to 2x3.
+        let px: Vec<u8> = (0..6).collect();
+        let (out, w, h) = apply_orientation(&px, 3, 2, 1, 6);
+        assert_eq!((w, h), (2, 3));
+        assert_eq!(out.len(), px.len());
+    }
+
+    #[test]
+    fn orientation_transpose_is_its_own_inverse() {
+        // Transposing (5) twice returns the original buffer and dimensions.
+        let px: Vec<u8> = (0..12).collect();
+        let (once, w1, h1) = apply_orientation(&px, 4, 3, 1, 5);
+        let (twice, w2, h2) = apply_orientation(&once, w1, h1, 1, 5);
+        assert_eq!((w2, h2), (4, 3));
+        assert_eq!(twice, px);
+    }
+
+    #[test]
+    fn normalize_exif_resets_orientation_tag() {
+        // Little-endian TIFF, one IFD0 entry: Orientation (0x0112) SHORT = 6.
+        let exif: Vec<u8> = vec![
+            0x49, 0x49, 0x2A, 0x00, // "II", magic
+            0x08, 0x00, 0x00, 0x00, // IFD0 at offset 8
+            0x01, 0x00, // entry count = 1
+            0x12, 0x01, // tag 0x0112
+            0x03, 0x00, // type SHORT
+            0x01, 0x00, 0x00, 0x00, // count 1
+            0x06, 0x00, 0x00, 0x00, // value 6
+        ];
+        let out = normalize_exif_orientation(&exif);
+        assert_eq!(out[18], 1);
+        assert_eq!(out[19], 0);
+    }
+
+    #[test]
+    fn normalize_exif_without_tiff_header_is_passthrough() {
+        let junk = vec![0u8, 1, 2, 3, 4, 5];
+        assert_eq!(normalize_exif_orientation(&junk), junk);
+    }
+
+    #[test]
+    fn changed_bounds_detects_rect_and_identity() {
+        let prev = vec![0u8; 3 * 3];
+        let mut next = prev.clone();
+        next[1 * 3 + 1] = 1; // centre pixel differs
+        assert_eq!(changed_bounds(&prev, &next, 3, 3, 1), Some((1, 1, 1, 1)));
+        assert_eq!(changed_bounds(&prev, &prev, 3, 3, 1), None);
+    }
+}